@@ -0,0 +1,67 @@
+extern crate regex;
+
+use regex::Regex;
+
+/// A parsed `[notice] Bootstrapped NN% (tag): summary` line from tor's stdout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootstrapEvent {
+    pub percent: u8,
+    pub tag: String,
+    pub summary: String,
+}
+
+/// A progress event surfaced while tor is starting up.
+///
+/// Passed to the callback configured via `TorProcess::on_progress` so a caller can show live
+/// status (e.g. "connecting to directory server") instead of only blocking until
+/// `completion_percent` is reached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressEvent {
+    Bootstrap(BootstrapEvent),
+    Warning(String),
+}
+
+// Parses a `[notice] Bootstrapped NN% (tag): summary` line (the tag is optional, matching
+// tor versions that omit it). Returns `None` if the line doesn't match the expected shape.
+//
+// Only called once per bootstrap line tor prints during startup, so recompiling the
+// regex on every call rather than caching it isn't worth the extra state.
+pub fn parse_bootstrap_line(line: &str) -> Option<BootstrapEvent> {
+    let re_bootstrap =
+        Regex::new(r"^\[notice\] Bootstrapped (?P<perc>[0-9]+)%(?: \((?P<tag>[^)]+)\))?: (?P<summary>.*)$")
+            .unwrap();
+    let captures = re_bootstrap.captures(line)?;
+    let percent = captures.name("perc")?.as_str().parse::<u8>().ok()?;
+    let tag = captures.name("tag").map_or(String::new(), |m| m.as_str().to_string());
+    let summary = captures.name("summary").map_or(String::new(), |m| m.as_str().to_string());
+
+    Some(BootstrapEvent { percent, tag, summary })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tagged_bootstrap_line() {
+        let event = parse_bootstrap_line("[notice] Bootstrapped 45% (conn): Connecting to a relay")
+            .expect("line should match");
+        assert_eq!(event.percent, 45);
+        assert_eq!(event.tag, "conn");
+        assert_eq!(event.summary, "Connecting to a relay");
+    }
+
+    #[test]
+    fn parses_untagged_bootstrap_line() {
+        let event = parse_bootstrap_line("[notice] Bootstrapped 100%: Done")
+            .expect("line should match");
+        assert_eq!(event.percent, 100);
+        assert_eq!(event.tag, "");
+        assert_eq!(event.summary, "Done");
+    }
+
+    #[test]
+    fn rejects_non_bootstrap_notice_line() {
+        assert!(parse_bootstrap_line("[notice] Something else entirely").is_none());
+    }
+}
@@ -0,0 +1,204 @@
+use std::io;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// A non-`250` status line, carrying the three-digit code and the reply text.
+    Reply(String, String),
+    UnexpectedReply(String),
+}
+
+/// Credentials used to authenticate a `TorControl` connection, mirroring the two schemes tor's
+/// control protocol supports: reading back the cookie it wrote to disk, or a password matching
+/// the `HashedControlPassword` configured in the torrc.
+pub enum Authentication {
+    Cookie(String),
+    HashedPassword(String),
+}
+
+/// A connection to tor's control port, speaking the line-based control protocol described in
+/// `control-spec.txt`. Replaces scraping stdout for status with real `GETINFO`/`SIGNAL`/
+/// `GETCONF`/`SETCONF` round-trips.
+pub struct TorControl {
+    stream: BufReader<TcpStream>,
+}
+
+impl TorControl {
+    pub async fn connect(addr: &str) -> Result<Self, Error> {
+        let stream = TcpStream::connect(addr).await.map_err(Error::Io)?;
+        Ok(TorControl { stream: BufReader::new(stream) })
+    }
+
+    pub async fn authenticate(&mut self, auth: &Authentication) -> Result<(), Error> {
+        let token = match *auth {
+            Authentication::Cookie(ref cookie_path) => {
+                let cookie = tokio::fs::read(cookie_path).await.map_err(Error::Io)?;
+                hex_encode(&cookie)
+            }
+            Authentication::HashedPassword(ref password) => {
+                format!("\"{}\"", quote_escape(password))
+            }
+        };
+        self.send_command(&format!("AUTHENTICATE {}", token)).await.map(|_| ())
+    }
+
+    pub async fn get_info(&mut self, keyword: &str) -> Result<Vec<String>, Error> {
+        self.send_command(&format!("GETINFO {}", keyword)).await
+    }
+
+    pub async fn signal(&mut self, signal: &str) -> Result<Vec<String>, Error> {
+        self.send_command(&format!("SIGNAL {}", signal)).await
+    }
+
+    pub async fn get_conf(&mut self, keyword: &str) -> Result<Vec<String>, Error> {
+        self.send_command(&format!("GETCONF {}", keyword)).await
+    }
+
+    pub async fn set_conf(&mut self, keyword: &str, value: &str) -> Result<Vec<String>, Error> {
+        self.send_command(&format!("SETCONF {}={}", keyword, value)).await
+    }
+
+    async fn send_command(&mut self, command: &str) -> Result<Vec<String>, Error> {
+        let stream = self.stream.get_mut();
+        stream.write_all(command.as_bytes()).await.map_err(Error::Io)?;
+        stream.write_all(b"\r\n").await.map_err(Error::Io)?;
+        self.read_reply().await
+    }
+
+    // Control replies are one or more lines of `CCC<sep>text`, where `CCC` is the status code
+    // and `<sep>` is `-` for a line with more to follow, ` ` for the last line of a reply, or
+    // `+` for a multi-line data block (e.g. `GETINFO config-text`) terminated by a lone `.`.
+    async fn read_reply(&mut self) -> Result<Vec<String>, Error> {
+        let mut lines = Vec::new();
+        loop {
+            let raw_line = self.read_line().await?;
+            let reply = parse_reply_line(&raw_line)?;
+
+            if reply.code != "250" {
+                return Err(Error::Reply(reply.code, reply.text));
+            }
+
+            match reply.sep {
+                ' ' => {
+                    lines.push(reply.text);
+                    break;
+                }
+                '-' => lines.push(reply.text),
+                '+' => {
+                    lines.push(reply.text);
+                    loop {
+                        let data_line = self.read_line().await?;
+                        if data_line == "." {
+                            break;
+                        }
+                        lines.push(unstuff_dot(data_line));
+                    }
+                }
+                _ => return Err(Error::UnexpectedReply(raw_line)),
+            }
+        }
+        Ok(lines)
+    }
+
+    async fn read_line(&mut self) -> Result<String, Error> {
+        let mut raw_line = String::new();
+        self.stream.read_line(&mut raw_line).await.map_err(Error::Io)?;
+        Ok(raw_line.trim_end_matches(|c| c == '\r' || c == '\n').to_string())
+    }
+}
+
+struct ReplyLine {
+    code: String,
+    sep: char,
+    text: String,
+}
+
+// Splits a `CCC<sep>text` status line into its status code, separator, and text. Does not
+// itself reject non-`250` codes -- that's a protocol decision made by the caller.
+fn parse_reply_line(line: &str) -> Result<ReplyLine, Error> {
+    if line.len() < 4 {
+        return Err(Error::UnexpectedReply(line.to_string()));
+    }
+    let code = line[..3].to_string();
+    let sep = line[3..4].chars().next().unwrap();
+    let text = line[4..].to_string();
+    Ok(ReplyLine { code, sep, text })
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Escapes `\` and `"` so `value` is safe to interpolate into a control-spec QuotedString;
+// `\` must be escaped first so a literal backslash isn't mistaken for the start of the escape
+// sequence this function just inserted for a quote.
+fn quote_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Undoes dot-stuffing: a data-block line starting with `.` has an extra leading `.` inserted
+// so it isn't mistaken for the block's lone-`.` terminator.
+fn unstuff_dot(line: String) -> String {
+    line.strip_prefix('.').map(|stripped| stripped.to_string()).unwrap_or(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_encodes_bytes() {
+        assert_eq!(hex_encode(&[0x00, 0xab, 0xff]), "00abff");
+    }
+
+    #[test]
+    fn parses_continuation_reply_line() {
+        let reply = parse_reply_line("250-version=0.4.7.13").unwrap();
+        assert_eq!(reply.code, "250");
+        assert_eq!(reply.sep, '-');
+        assert_eq!(reply.text, "version=0.4.7.13");
+    }
+
+    #[test]
+    fn parses_final_reply_line() {
+        let reply = parse_reply_line("250 OK").unwrap();
+        assert_eq!(reply.code, "250");
+        assert_eq!(reply.sep, ' ');
+        assert_eq!(reply.text, "OK");
+    }
+
+    #[test]
+    fn parses_data_block_start_line() {
+        let reply = parse_reply_line("250+config-text=").unwrap();
+        assert_eq!(reply.sep, '+');
+        assert_eq!(reply.text, "config-text=");
+    }
+
+    #[test]
+    fn rejects_lines_shorter_than_four_chars() {
+        assert!(parse_reply_line("25").is_err());
+    }
+
+    #[test]
+    fn quote_escape_escapes_backslash_before_quote() {
+        assert_eq!(quote_escape(r#"p\"w"#), r#"p\\\"w"#);
+    }
+
+    #[test]
+    fn quote_escape_leaves_plain_password_untouched() {
+        assert_eq!(quote_escape("hunter2"), "hunter2");
+    }
+
+    #[test]
+    fn unstuff_dot_removes_one_leading_dot() {
+        assert_eq!(unstuff_dot("..still dotted".to_string()), ".still dotted");
+    }
+
+    #[test]
+    fn unstuff_dot_leaves_undotted_lines_untouched() {
+        assert_eq!(unstuff_dot("plain line".to_string()), "plain line");
+    }
+}
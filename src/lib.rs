@@ -0,0 +1,8 @@
+#[macro_use]
+extern crate log;
+
+pub mod bootstrap;
+pub mod control;
+pub mod metrics;
+pub mod process;
+pub mod torrc;
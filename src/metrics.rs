@@ -0,0 +1,98 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Receives the lifecycle events emitted around a tor process launch.
+///
+/// Implement this to forward events to `metrics`, logs, or wherever else; `NoopRecorder`
+/// discards everything and is the default so instrumentation is opt-in.
+pub trait MetricsRecorder {
+    fn start(&self, command: &str);
+    fn duration(&self, command: &str, seconds: f64, completed: bool);
+    fn end(&self, command: &str, completed: bool);
+}
+
+/// Default recorder used when the caller hasn't wired one up.
+pub struct NoopRecorder;
+
+impl MetricsRecorder for NoopRecorder {
+    fn start(&self, _command: &str) {}
+    fn duration(&self, _command: &str, _seconds: f64, _completed: bool) {}
+    fn end(&self, _command: &str, _completed: bool) {}
+}
+
+/// Tracks a tor process lifecycle and emits start/duration/end records through a
+/// `MetricsRecorder`.
+///
+/// `armed` starts `true`, meaning the guarded operation is assumed to abort; call
+/// `disarm()` once the bootstrap completes so the duration/end records emitted from `Drop`
+/// are tagged `completed` instead of `aborted`, even if the caller returns early or panics
+/// before the guard would otherwise be dropped explicitly.
+pub struct MetricsGuard {
+    recorder: Arc<dyn MetricsRecorder + Send + Sync>,
+    command: String,
+    start: Instant,
+    armed: bool,
+}
+
+impl MetricsGuard {
+    pub fn new(recorder: Arc<dyn MetricsRecorder + Send + Sync>, command: &str) -> Self {
+        recorder.start(command);
+        MetricsGuard {
+            recorder,
+            command: command.to_string(),
+            start: Instant::now(),
+            armed: true,
+        }
+    }
+
+    pub fn disarm(&mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for MetricsGuard {
+    fn drop(&mut self) {
+        let completed = !self.armed;
+        let seconds = self.start.elapsed().as_secs_f64();
+        self.recorder.duration(&self.command, seconds, completed);
+        self.recorder.end(&self.command, completed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingRecorder {
+        ended: Mutex<Vec<bool>>,
+    }
+
+    impl MetricsRecorder for RecordingRecorder {
+        fn start(&self, _command: &str) {}
+        fn duration(&self, _command: &str, _seconds: f64, _completed: bool) {}
+        fn end(&self, _command: &str, completed: bool) {
+            self.ended.lock().unwrap().push(completed);
+        }
+    }
+
+    #[test]
+    fn disarm_tags_end_as_completed() {
+        let recorder = Arc::new(RecordingRecorder::default());
+        {
+            let mut guard = MetricsGuard::new(recorder.clone(), "tor");
+            guard.disarm();
+        }
+        assert_eq!(*recorder.ended.lock().unwrap(), vec![true]);
+    }
+
+    #[test]
+    fn dropping_without_disarm_tags_end_as_aborted() {
+        let recorder = Arc::new(RecordingRecorder::default());
+        {
+            let _guard = MetricsGuard::new(recorder.clone(), "tor");
+        }
+        assert_eq!(*recorder.ended.lock().unwrap(), vec![false]);
+    }
+}
@@ -1,13 +1,23 @@
+extern crate libc;
 extern crate regex;
-extern crate timer;
-extern crate chrono;
 
 use std::io;
-use std::thread;
-use std::process::{Command, Stdio, Child, ChildStdout};
-use std::io::{BufReader, BufRead};
-use std::sync::mpsc::channel;
-use regex::Regex;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, ChildStdout, Command};
+use tokio::time;
+
+use crate::bootstrap;
+use crate::bootstrap::ProgressEvent;
+use crate::control::TorControl;
+use crate::metrics::{MetricsGuard, MetricsRecorder, NoopRecorder};
+use crate::torrc::{self, Torrc};
+
+// Grace period given to tor's own shutdown handling in `Drop` before falling back to `kill`.
+const DEFAULT_DROP_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Debug)]
 pub enum Error {
@@ -18,6 +28,7 @@ pub enum Error {
     Regex(regex::Error),
     ProcessNotStarted,
     Timeout,
+    Torrc(torrc::Error),
 }
 
 pub struct TorProcess {
@@ -26,6 +37,10 @@ pub struct TorProcess {
     torrc_path: Option<String>,
     completion_percent: u8,
     timeout: u32,
+    control_port: Option<u16>,
+    metrics_recorder: Arc<dyn MetricsRecorder + Send + Sync>,
+    progress_callback: Option<Arc<dyn Fn(ProgressEvent) + Send + Sync>>,
+    control: Option<TorControl>,
     pub stdout: Option<BufReader<ChildStdout>>,
     pub process: Option<Child>,
 }
@@ -38,6 +53,10 @@ impl TorProcess {
             torrc_path: None,
             completion_percent: 100 as u8,
             timeout: 0 as u32,
+            control_port: None,
+            metrics_recorder: Arc::new(NoopRecorder),
+            progress_callback: None,
+            control: None,
             stdout: None,
             process: None,
         }
@@ -53,6 +72,13 @@ impl TorProcess {
         self
     }
 
+    // Renders `torrc` to a temp file and points `torrc_path` at it, so a caller can compose a
+    // config with `Torrc` instead of hand-writing a file to pass here.
+    pub fn torrc(&mut self, torrc: &Torrc) -> Result<&mut Self, Error> {
+        let path = torrc.write().map_err(Error::Torrc)?;
+        Ok(self.torrc_path(path.to_string_lossy().as_ref()))
+    }
+
     pub fn arg(&mut self, arg: String) -> &mut Self {
         self.args.push(arg);
         self
@@ -75,61 +101,110 @@ impl TorProcess {
         self
     }
 
+    // Opens `port` as a cookie-authenticated control port, so a `TorControl` can connect to it
+    // once tor has finished bootstrapping. Mutually exclusive with `Torrc::control_port`: if a
+    // config file is also supplied (via `torrc` or `torrc_path`), that file is the single source
+    // of truth for `ControlPort`/`CookieAuthentication` and this setting is ignored by `launch`,
+    // so tor doesn't reject the process for specifying `ControlPort` twice.
+    pub fn control_port(&mut self, port: u16) -> &mut Self {
+        self.control_port = Some(port);
+        self
+    }
+
+    // Registers an authenticated control connection so `shutdown` can ask tor to stop cleanly
+    // via `SIGNAL SHUTDOWN` instead of only signalling the child process.
+    pub fn set_control(&mut self, control: TorControl) -> &mut Self {
+        self.control = Some(control);
+        self
+    }
+
+    pub fn metrics_recorder(&mut self,
+                             metrics_recorder: Arc<dyn MetricsRecorder + Send + Sync>)
+                             -> &mut Self {
+        self.metrics_recorder = metrics_recorder;
+        self
+    }
+
+    // Receives a `ProgressEvent` for every bootstrap/warning line tor prints, so a caller can
+    // show live status instead of only finding out once `completion_percent` is reached.
+    pub fn on_progress<F>(&mut self, callback: F) -> &mut Self
+        where F: Fn(ProgressEvent) + Send + Sync + 'static
+    {
+        self.progress_callback = Some(Arc::new(callback));
+        self
+    }
+
     // The tor process will have its stdout piped, so if the stdout lines are not consumed they
     // will keep accumulating over time, increasing the consumed memory.
-    pub fn launch(&mut self) -> Result<&mut Self, Error> {
+    //
+    // The bootstrap wait is bounded by `timeout` via `tokio::time::timeout`, so callers get a
+    // single future that either resolves with `self` once the configured completion percentage
+    // is reached, or fails with `Error::Timeout`/`Error::Tor` -- there is no separate timer guard
+    // or channel to join like the blocking implementation needed. If tor's stdout closes before
+    // that percentage is reached, the future also resolves with `self`. `timeout == 0` (the default)
+    // means "no timeout"; it does not mean "time out immediately".
+    pub async fn launch(&mut self) -> Result<&mut Self, Error> {
+        let mut metrics = MetricsGuard::new(self.metrics_recorder.clone(), &self.tor_cmd);
+
         let mut tor = Command::new(&self.tor_cmd);
         if let Some(ref torrc_path) = self.torrc_path {
             tor.args(&vec!["-f", torrc_path]);
         }
+        // `control_port`'s CLI args and a torrc's own `ControlPort`/`CookieAuthentication`
+        // lines are mutually exclusive sources of truth -- passing both makes tor reject the
+        // process for specifying `ControlPort` twice, so a torrc file wins when present.
+        if self.torrc_path.is_none() {
+            if let Some(control_port) = self.control_port {
+                tor.args(&vec!["--ControlPort", &control_port.to_string()]);
+                tor.args(&vec!["--CookieAuthentication", "1"]);
+            }
+        }
         let mut tor_process = tor.args(&self.args)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
+            .kill_on_drop(true)
             .spawn()
             .map_err(|err| Error::Process(err))?;
         let stdout = BufReader::new(tor_process.stdout.take().unwrap());
 
         self.process = Some(tor_process);
         let completion_percent = self.completion_percent;
+        let progress_callback = self.progress_callback.clone();
 
-        let (stdout_tx, stdout_rx) = channel();
-        let stdout_timeout_tx = stdout_tx.clone();
-
-        let timer = timer::Timer::new();
-        let _guard = timer.schedule_with_delay(chrono::Duration::seconds(self.timeout as i64),
-                                               move || {
-                                                   stdout_timeout_tx.send(Err(Error::Timeout))
-                                                                    .unwrap_or(());
-                                               });
-        let stdout_thread = thread::spawn(move || {
-            stdout_tx.send(Self::parse_tor_stdout(stdout, completion_percent)).unwrap_or(());
-        });
-        match stdout_rx.recv().unwrap() {
-            Ok(stdout) => {
-                stdout_thread.join().unwrap();
+        let parse = Self::parse_tor_stdout(stdout, completion_percent, progress_callback);
+        let parse_result = if self.timeout == 0 {
+            Ok(parse.await)
+        } else {
+            time::timeout(Duration::from_secs(self.timeout as u64), parse).await
+        };
+
+        match parse_result {
+            Ok(Ok(stdout)) => {
+                metrics.disarm();
                 self.stdout = Some(stdout);
                 Ok(self)
             }
-            Err(err) => {
-                self.kill().unwrap_or(());
-                stdout_thread.join().unwrap();
+            Ok(Err(err)) => {
+                self.kill().await.unwrap_or(());
                 Err(err)
             }
+            Err(_elapsed) => {
+                self.kill().await.unwrap_or(());
+                Err(Error::Timeout)
+            }
         }
     }
 
-    fn parse_tor_stdout(mut stdout: BufReader<ChildStdout>,
-                        completion_perc: u8)
-                        -> Result<BufReader<ChildStdout>, Error> {
-        let re_bootstrap = Regex::new(r"^\[notice\] Bootstrapped (?P<perc>[0-9]+)%: ")
-            .map_err(|err| Error::Regex(err))?;
-
+    async fn parse_tor_stdout(mut stdout: BufReader<ChildStdout>,
+                              completion_perc: u8,
+                              progress_callback: Option<Arc<dyn Fn(ProgressEvent) + Send + Sync>>)
+                              -> Result<BufReader<ChildStdout>, Error> {
         let timestamp_len = "May 16 02:50:08.792".len();
         let mut warnings = Vec::new();
         let mut raw_line = String::new();
 
-        while stdout.read_line(&mut raw_line).map_err(|err| Error::Process(err))? > 0 {
+        while stdout.read_line(&mut raw_line).await.map_err(|err| Error::Process(err))? > 0 {
             {
                 if raw_line.len() < timestamp_len + 1 {
                     return Err(Error::InvalidLogLine);
@@ -140,17 +215,25 @@ impl TorProcess {
                 match line.split(' ').nth(0) {
                     Some("[notice]") => {
                         if let Some("Bootstrapped") = line.split(' ').nth(1) {
-                            let perc = re_bootstrap.captures(line)
-                                .and_then(|c| c.name("perc"))
-                                .and_then( |pc| pc.as_str().parse::<u8>().ok())
-                                .ok_or(Error::InvalidBootstrapLine(line.to_string()))?;
+                            let event = bootstrap::parse_bootstrap_line(line)
+                                .ok_or_else(|| Error::InvalidBootstrapLine(line.to_string()))?;
+                            let perc = event.percent;
+
+                            if let Some(ref callback) = progress_callback {
+                                callback(ProgressEvent::Bootstrap(event));
+                            }
 
                             if perc >= completion_perc {
                                 break;
                             }
                         }
                     }
-                    Some("[warn]") => warnings.push(line.to_string()),
+                    Some("[warn]") => {
+                        if let Some(ref callback) = progress_callback {
+                            callback(ProgressEvent::Warning(line.to_string()));
+                        }
+                        warnings.push(line.to_string());
+                    }
                     Some("[err]") => return Err(Error::Tor(line.to_string(), warnings)),
                     _ => (),
                 }
@@ -160,18 +243,83 @@ impl TorProcess {
         Ok(stdout)
     }
 
-    pub fn kill(&mut self) -> Result<(), Error> {
+    pub async fn kill(&mut self) -> Result<(), Error> {
         if let Some(ref mut process) = self.process {
-            Ok(process.kill().map_err(|err| Error::Process(err))?)
+            Ok(process.kill().await.map_err(|err| Error::Process(err))?)
         } else {
             Err(Error::ProcessNotStarted)
         }
     }
+
+    // Asks tor to stop cleanly -- over the control connection if one is configured via
+    // `set_control`, otherwise by signalling the child with SIGTERM -- and waits up to
+    // `timeout` for it to exit before falling back to `kill`. This lets tor flush state and
+    // close circuits instead of losing them to a SIGKILL, and lets hidden services deregister.
+    //
+    // Takes `process`/`control` so that once a graceful shutdown has run, `self.process` is
+    // `None` and `Drop` is a no-op -- otherwise `Drop` would see a live `process` and run
+    // `graceful_shutdown` again on a child the OS has already reaped, signalling whatever pid
+    // the kernel has since reused.
+    pub async fn shutdown(&mut self, timeout: Duration) -> Result<(), Error> {
+        let process = self.process.take().ok_or(Error::ProcessNotStarted)?;
+        let control = self.control.take();
+        graceful_shutdown(process, control, timeout).await;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn terminate(process: &mut Child) {
+    if let Some(pid) = process.id() {
+        unsafe {
+            libc::kill(pid as libc::pid_t, libc::SIGTERM);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn terminate(process: &mut Child) {
+    process.start_kill().unwrap_or(());
+}
+
+// Runs the graceful-shutdown sequence for a child (and optional control connection) that
+// `shutdown` or `Drop` has taken ownership of. This is a free function operating on the raw `Child`/`TorControl`
+// rather than a `TorProcess`, so the detached task running it never re-enters `TorProcess::drop`
+// and spawns another cleanup task -- which, since a just-exited child's `wait`/`signal` resolve
+// instantly, would otherwise busy-loop spawning tasks forever.
+async fn graceful_shutdown(mut process: Child, control: Option<TorControl>, timeout: Duration) {
+    match control {
+        Some(mut control) => {
+            control.signal("SHUTDOWN").await.unwrap_or(vec![]);
+        }
+        None => terminate(&mut process),
+    }
+
+    if time::timeout(timeout, process.wait()).await.is_err() {
+        process.start_kill().unwrap_or(());
+    }
 }
 
 impl Drop for TorProcess {
-    // kill the child
+    // Best-effort graceful shutdown: `shutdown` is async and Drop cannot await it directly, so
+    // if we're running inside a tokio runtime we hand the raw child (and control connection) off
+    // to a detached task running `graceful_shutdown` with a short default timeout. Outside a
+    // runtime there's no way to wait, so we fall back to an immediate kill.
     fn drop(&mut self) {
-        self.kill().unwrap_or(());
+        let process = match self.process.take() {
+            Some(process) => process,
+            None => return,
+        };
+        let control = self.control.take();
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(graceful_shutdown(process, control, DEFAULT_DROP_SHUTDOWN_TIMEOUT));
+            }
+            Err(_) => {
+                let mut process = process;
+                process.start_kill().unwrap_or(());
+            }
+        }
     }
 }
@@ -0,0 +1,143 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+}
+
+static NEXT_TORRC_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone)]
+struct HiddenService {
+    dir: String,
+    ports: Vec<(u16, String)>,
+}
+
+/// Builds a torrc in memory, so callers can compose a tor configuration programmatically
+/// instead of hand-writing a config file for `TorProcess::torrc_path`.
+#[derive(Debug, Clone, Default)]
+pub struct Torrc {
+    socks_port: Option<u16>,
+    data_directory: Option<String>,
+    control_port: Option<u16>,
+    hidden_services: Vec<HiddenService>,
+}
+
+impl Torrc {
+    pub fn new() -> Self {
+        Torrc::default()
+    }
+
+    pub fn socks_port(&mut self, port: u16) -> &mut Self {
+        self.socks_port = Some(port);
+        self
+    }
+
+    pub fn data_directory(&mut self, dir: &str) -> &mut Self {
+        self.data_directory = Some(dir.to_string());
+        self
+    }
+
+    // Mutually exclusive with `TorProcess::control_port`: when this `Torrc` is passed to
+    // `TorProcess::torrc`, the rendered `ControlPort`/`CookieAuthentication` lines are the
+    // single source of truth and `TorProcess::control_port`'s CLI args are skipped, so tor
+    // doesn't reject the process for specifying `ControlPort` twice.
+    pub fn control_port(&mut self, port: u16) -> &mut Self {
+        self.control_port = Some(port);
+        self
+    }
+
+    // Starts a `HiddenServiceDir` stanza; call `hidden_service_port` to add one or more
+    // `HiddenServicePort` lines under it.
+    pub fn hidden_service(&mut self, dir: &str) -> &mut Self {
+        self.hidden_services.push(HiddenService {
+            dir: dir.to_string(),
+            ports: vec![],
+        });
+        self
+    }
+
+    pub fn hidden_service_port(&mut self, virt_port: u16, target: &str) -> &mut Self {
+        if let Some(service) = self.hidden_services.last_mut() {
+            service.ports.push((virt_port, target.to_string()));
+        }
+        self
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        if let Some(port) = self.socks_port {
+            out.push_str(&format!("SocksPort {}\n", port));
+        }
+        if let Some(ref dir) = self.data_directory {
+            out.push_str(&format!("DataDirectory {}\n", dir));
+        }
+        if let Some(port) = self.control_port {
+            out.push_str(&format!("ControlPort {}\n", port));
+            out.push_str("CookieAuthentication 1\n");
+        }
+        for service in &self.hidden_services {
+            out.push_str(&format!("HiddenServiceDir {}\n", service.dir));
+            for &(virt_port, ref target) in &service.ports {
+                out.push_str(&format!("HiddenServicePort {} {}\n", virt_port, target));
+            }
+        }
+        out
+    }
+
+    // Renders the config to a uniquely-named file under the system temp directory and returns
+    // its path, ready to hand to `TorProcess::torrc_path`.
+    pub fn write(&self) -> Result<PathBuf, Error> {
+        let id = NEXT_TORRC_ID.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("torrc-{}-{}", std::process::id(), id));
+        let mut file = File::create(&path).map_err(Error::Io)?;
+        file.write_all(self.render().as_bytes()).map_err(Error::Io)?;
+        Ok(path)
+    }
+
+    // Reads back the `.onion` address tor writes to `hostname` once it has created the
+    // hidden-service directory passed to `hidden_service`.
+    pub fn hidden_service_hostname(service_dir: &str) -> Result<String, Error> {
+        let hostname_path = std::path::Path::new(service_dir).join("hostname");
+        let contents = std::fs::read_to_string(hostname_path).map_err(Error::Io)?;
+        Ok(contents.trim().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_ports_and_data_directory() {
+        let mut torrc = Torrc::new();
+        torrc.socks_port(9050).data_directory("/tmp/tor-data").control_port(9051);
+
+        let rendered = torrc.render();
+        assert!(rendered.contains("SocksPort 9050\n"));
+        assert!(rendered.contains("DataDirectory /tmp/tor-data\n"));
+        assert!(rendered.contains("ControlPort 9051\n"));
+        assert!(rendered.contains("CookieAuthentication 1\n"));
+    }
+
+    #[test]
+    fn renders_hidden_service_stanza_with_multiple_ports_in_order() {
+        let mut torrc = Torrc::new();
+        torrc.hidden_service("/var/lib/tor/hidden_service")
+            .hidden_service_port(80, "127.0.0.1:8080")
+            .hidden_service_port(443, "127.0.0.1:8443");
+
+        let expected = "HiddenServiceDir /var/lib/tor/hidden_service\n\
+                         HiddenServicePort 80 127.0.0.1:8080\n\
+                         HiddenServicePort 443 127.0.0.1:8443\n";
+        assert_eq!(torrc.render(), expected);
+    }
+
+    #[test]
+    fn renders_nothing_for_an_empty_torrc() {
+        assert_eq!(Torrc::new().render(), "");
+    }
+}